@@ -1,13 +1,645 @@
 use rmcp::model::{Implementation, ProtocolVersion, ServerCapabilities, ServerInfo};
 use rmcp::{ServerHandler, schemars, tool};
 use std::fs;
-use std::path::Path;
-use std::process::Command;
-use grep::regex::RegexMatcher;
-use grep::searcher::{BinaryDetection, SearcherBuilder};
-use ignore::WalkBuilder;
+use std::path::{Path, PathBuf};
+use grep::matcher::{Match, Matcher, NoCaptures};
+#[cfg(feature = "pcre2")]
+use grep::pcre2::{RegexMatcher as Pcre2RegexMatcher, RegexMatcherBuilder as Pcre2RegexMatcherBuilder};
+use grep::regex::RegexMatcher as DefaultRegexMatcher;
+use grep::regex::RegexMatcherBuilder;
+use grep::searcher::{BinaryDetection, Searcher, SearcherBuilder, Sink, SinkContext, SinkMatch};
+use ignore::overrides::OverrideBuilder;
+use ignore::types::TypesBuilder;
+use ignore::{WalkBuilder, WalkState};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 use tracing;
 
+// Maximum number of result lines collected across the whole search (matched,
+// context and separator lines in `matches` mode; one line per file in the
+// `count` and `files` modes), so a run over a huge tree can't blow up the
+// response.
+const MAX_SEARCH_RESULTS: usize = 100;
+
+// A regex engine chosen from `SearchParams::engine`. Wrapping both matcher
+// types behind one `Matcher` impl lets the rest of the search pipeline stay
+// oblivious to which engine built it.
+#[derive(Clone)]
+enum AnySearchMatcher {
+    Default(DefaultRegexMatcher),
+    #[cfg(feature = "pcre2")]
+    Pcre2(Pcre2RegexMatcher),
+}
+
+#[derive(Debug)]
+struct AnyMatcherError(String);
+
+impl std::fmt::Display for AnyMatcherError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for AnyMatcherError {}
+
+impl Matcher for AnySearchMatcher {
+    type Captures = NoCaptures;
+    type Error = AnyMatcherError;
+
+    fn find_at(&self, haystack: &[u8], at: usize) -> Result<Option<Match>, Self::Error> {
+        match self {
+            AnySearchMatcher::Default(m) => m
+                .find_at(haystack, at)
+                .map_err(|e| AnyMatcherError(e.to_string())),
+            #[cfg(feature = "pcre2")]
+            AnySearchMatcher::Pcre2(m) => m
+                .find_at(haystack, at)
+                .map_err(|e| AnyMatcherError(e.to_string())),
+        }
+    }
+
+    fn new_captures(&self) -> Result<Self::Captures, Self::Error> {
+        Ok(NoCaptures::new())
+    }
+}
+
+// Build the matcher for the engine named in `SearchParams::engine`
+// ("default" or "pcre2"), returning a clear error if the pattern is
+// rejected by the chosen engine or PCRE2 support wasn't compiled in.
+fn build_matcher(keyword: &str, engine: &str) -> Result<AnySearchMatcher, String> {
+    match engine {
+        "" | "default" => RegexMatcherBuilder::new()
+            .case_insensitive(true)
+            .build(keyword)
+            .map(AnySearchMatcher::Default)
+            .map_err(|e| format!("Invalid search pattern: {}", e)),
+        "pcre2" => {
+            #[cfg(feature = "pcre2")]
+            {
+                Pcre2RegexMatcherBuilder::new()
+                    .caseless(true)
+                    .build(keyword)
+                    .map(AnySearchMatcher::Pcre2)
+                    .map_err(|e| format!("Invalid PCRE2 pattern: {}", e))
+            }
+            #[cfg(not(feature = "pcre2"))]
+            {
+                Err("This build was compiled without PCRE2 support (enable the `pcre2` feature)".to_string())
+            }
+        }
+        other => Err(format!(
+            "Unknown search engine '{}'; expected \"default\" or \"pcre2\"",
+            other
+        )),
+    }
+}
+
+// Decode raw file bytes to text using the named encoding, or auto-detect a
+// leading UTF-8/UTF-16LE/UTF-16BE BOM, falling back to UTF-8 with lossy
+// replacement. Guards against genuinely binary files up front, the same
+// heuristic this tool has always used, but only when there's no BOM to
+// prove the bytes are text.
+fn decode_file_bytes(bytes: &[u8], encoding_name: Option<&str>, display_path: &str) -> Result<String, String> {
+    let has_bom = encoding_rs::Encoding::for_bom(bytes).is_some();
+
+    if encoding_name.is_none()
+        && !has_bom
+        && (bytes.contains(&0)
+            || bytes
+                .iter()
+                .filter(|&&b| b < 32 && b != 9 && b != 10 && b != 13)
+                .count()
+                > bytes.len() / 10)
+    {
+        return Err(format!(
+            "The file '{}' appears to be a binary file and cannot be displayed as text",
+            display_path
+        ));
+    }
+
+    let encoding = match encoding_name {
+        Some(name) => encoding_rs::Encoding::for_label(name.as_bytes())
+            .ok_or_else(|| format!("Unknown encoding '{}'", name))?,
+        None => encoding_rs::UTF_8,
+    };
+
+    // `decode` sniffs a leading BOM and overrides `encoding` with it, so a
+    // UTF-16 file is handled correctly even if the caller didn't name it.
+    let (text, _, had_errors) = encoding.decode(bytes);
+    if had_errors {
+        tracing::warn!(
+            "File '{}' was decoded with replacement characters; it may not be valid for the chosen encoding",
+            display_path
+        );
+    }
+
+    Ok(text.into_owned())
+}
+
+// Read `start_line..=end_line` (1-based) of `file_path`, stopping early once
+// `max_bytes` of content has been produced. The binary-detection guard only
+// samples the first chunk of the file so the check doesn't itself require
+// reading a huge file into memory; the bulk of the file is then streamed
+// line by line with a `BufReader` rather than loaded all at once.
+fn read_file_range(
+    file_path: &Path,
+    encoding_name: Option<&str>,
+    start_line: Option<usize>,
+    end_line: Option<usize>,
+    max_bytes: Option<usize>,
+    display_path: &str,
+) -> Result<String, String> {
+    use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+
+    let start_line = start_line.unwrap_or(1).max(1);
+    if let Some(end) = end_line {
+        if end < start_line {
+            return Err(format!(
+                "end_line ({}) must be greater than or equal to start_line ({})",
+                end, start_line
+            ));
+        }
+    }
+
+    let mut file =
+        fs::File::open(file_path).map_err(|e| format!("Error reading file '{}': {}", display_path, e))?;
+
+    // Sample just enough of the file to run the usual binary-file guard.
+    let mut sample = vec![0u8; 8192];
+    let sample_len = file
+        .read(&mut sample)
+        .map_err(|e| format!("Error reading file '{}': {}", display_path, e))?;
+    sample.truncate(sample_len);
+
+    let has_bom = encoding_rs::Encoding::for_bom(&sample).is_some();
+    if encoding_name.is_none()
+        && !has_bom
+        && (sample.contains(&0)
+            || sample
+                .iter()
+                .filter(|&&b| b < 32 && b != 9 && b != 10 && b != 13)
+                .count()
+                > sample.len().max(1) / 10)
+    {
+        return Err(format!(
+            "The file '{}' appears to be a binary file and cannot be displayed as text",
+            display_path
+        ));
+    }
+
+    // A non-default encoding, or a BOM that needs to be sniffed and stripped,
+    // means the whole file has to be transcoded before lines can be sliced
+    // out of it; the line-buffered fast path below assumes the bytes are
+    // already UTF-8.
+    if encoding_name.is_some() || has_bom {
+        let bytes =
+            fs::read(file_path).map_err(|e| format!("Error reading file '{}': {}", display_path, e))?;
+        let content = decode_file_bytes(&bytes, encoding_name, display_path)?;
+        return Ok(slice_lines(&content, start_line, end_line, max_bytes));
+    }
+
+    file.seek(SeekFrom::Start(0))
+        .map_err(|e| format!("Error reading file '{}': {}", display_path, e))?;
+    let reader = BufReader::new(file);
+
+    let mut output = String::new();
+    let mut bytes_written = 0usize;
+
+    for (index, line) in reader.lines().enumerate() {
+        let line_number = index + 1;
+        if line_number < start_line {
+            continue;
+        }
+        if let Some(end) = end_line {
+            if line_number > end {
+                break;
+            }
+        }
+
+        let line = line.map_err(|e| format!("Error reading file '{}': {}", display_path, e))?;
+
+        if let Some(limit) = max_bytes {
+            if bytes_written + line.len() + 1 > limit {
+                let (written, next_start_line) =
+                    push_truncation_marker(&mut output, &line, bytes_written, limit, line_number);
+                output.push_str(&format!(
+                    "\n[... truncated after {} bytes; resume with start_line={} ...]",
+                    written, next_start_line
+                ));
+                return Ok(output);
+            }
+        }
+
+        output.push_str(&line);
+        output.push('\n');
+        bytes_written += line.len() + 1;
+    }
+
+    Ok(output)
+}
+
+// Slice an already-decoded string down to `start_line..=end_line`, stopping
+// early (with a truncation marker) once `max_bytes` of content is produced.
+fn slice_lines(content: &str, start_line: usize, end_line: Option<usize>, max_bytes: Option<usize>) -> String {
+    let mut output = String::new();
+    let mut bytes_written = 0usize;
+
+    for (index, line) in content.lines().enumerate() {
+        let line_number = index + 1;
+        if line_number < start_line {
+            continue;
+        }
+        if let Some(end) = end_line {
+            if line_number > end {
+                break;
+            }
+        }
+
+        if let Some(limit) = max_bytes {
+            if bytes_written + line.len() + 1 > limit {
+                let (written, next_start_line) =
+                    push_truncation_marker(&mut output, line, bytes_written, limit, line_number);
+                output.push_str(&format!(
+                    "\n[... truncated after {} bytes; resume with start_line={} ...]",
+                    written, next_start_line
+                ));
+                return output;
+            }
+        }
+
+        output.push_str(line);
+        output.push('\n');
+        bytes_written += line.len() + 1;
+    }
+
+    output
+}
+
+// Write whatever portion of `line` still fits within `limit` to `output`,
+// and return the bytes written plus the line number the caller should
+// resume pagination from. If nothing has been written yet, the line alone
+// exceeds the whole budget, so it is truncated in place and paged past
+// (`line_number + 1`) so pagination always converges; otherwise the line is
+// left untouched for the next call to retry with a fresh budget
+// (`line_number`).
+fn push_truncation_marker(
+    output: &mut String,
+    line: &str,
+    bytes_written: usize,
+    limit: usize,
+    line_number: usize,
+) -> (usize, usize) {
+    if bytes_written > 0 {
+        return (bytes_written, line_number);
+    }
+    let fits = truncate_to_byte_boundary(line, limit);
+    output.push_str(fits);
+    (fits.len(), line_number + 1)
+}
+
+// Truncate `s` to at most `limit` bytes without splitting a multi-byte
+// UTF-8 character.
+fn truncate_to_byte_boundary(s: &str, limit: usize) -> &str {
+    if s.len() <= limit {
+        return s;
+    }
+    let mut end = limit;
+    while end > 0 && !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+// How `search` should summarize its hits: every matched (and context) line,
+// a per-file match count, or just the set of files that matched at all.
+// Mirrors ripgrep's choice between its default printer and `Summary`/`Stats`
+// printer kinds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputMode {
+    Matches,
+    Count,
+    Files,
+}
+
+impl OutputMode {
+    fn parse(name: Option<&str>) -> Result<Self, String> {
+        match name.unwrap_or("matches") {
+            "matches" => Ok(OutputMode::Matches),
+            "count" => Ok(OutputMode::Count),
+            "files" => Ok(OutputMode::Files),
+            other => Err(format!(
+                "Unknown output_mode '{}'; expected \"matches\", \"count\", or \"files\"",
+                other
+            )),
+        }
+    }
+}
+
+// Accumulates hits in the shape `OutputMode` calls for: the full list of
+// formatted lines, a match count per file, or a set of matching files.
+enum SearchCollector {
+    Matches(Vec<String>),
+    Counts(HashMap<PathBuf, usize>),
+    Files(BTreeSet<PathBuf>),
+}
+
+impl SearchCollector {
+    fn new(mode: OutputMode) -> Self {
+        match mode {
+            OutputMode::Matches => SearchCollector::Matches(Vec::new()),
+            OutputMode::Count => SearchCollector::Counts(HashMap::new()),
+            OutputMode::Files => SearchCollector::Files(BTreeSet::new()),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            SearchCollector::Matches(hits) => hits.len(),
+            SearchCollector::Counts(counts) => counts.len(),
+            SearchCollector::Files(files) => files.len(),
+        }
+    }
+
+    fn record_hit_lines(&mut self, lines: Vec<String>) {
+        if let SearchCollector::Matches(hits) = self {
+            hits.extend(lines);
+        }
+    }
+
+    fn record_file_count(&mut self, path: &Path, count: usize) {
+        if count == 0 {
+            return;
+        }
+        match self {
+            SearchCollector::Counts(counts) => {
+                *counts.entry(path.to_path_buf()).or_insert(0) += count;
+            }
+            SearchCollector::Files(files) => {
+                files.insert(path.to_path_buf());
+            }
+            SearchCollector::Matches(_) => {}
+        }
+    }
+
+    // Render the collected hits as the lines `search` should join together.
+    fn into_lines(self) -> Vec<String> {
+        match self {
+            SearchCollector::Matches(hits) => hits,
+            SearchCollector::Counts(counts) => {
+                let mut entries: Vec<_> = counts.into_iter().collect();
+                entries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+                entries
+                    .into_iter()
+                    .map(|(path, count)| format!("{}: {}", path.display(), count))
+                    .collect()
+            }
+            SearchCollector::Files(files) => files.into_iter().map(|p| p.display().to_string()).collect(),
+        }
+    }
+}
+
+// A `Sink` that only counts matches in a file, short-circuiting once `limit`
+// is reached. Used for the `count` and `files` output modes, where the
+// matched text itself doesn't matter.
+struct CountSink {
+    limit: usize,
+    count: usize,
+}
+
+impl CountSink {
+    fn new(limit: usize) -> Self {
+        Self { limit, count: 0 }
+    }
+}
+
+impl Sink for CountSink {
+    type Error = std::io::Error;
+
+    fn matched(&mut self, _searcher: &Searcher, _mat: &SinkMatch<'_>) -> Result<bool, Self::Error> {
+        self.count += 1;
+        Ok(self.count < self.limit)
+    }
+}
+
+// Resolve `SearchParams::encoding` into the `grep::searcher::Encoding` the
+// `Searcher` should transcode matched files with before matching against
+// them. `None` means let the searcher assume UTF-8/sniff a BOM as usual.
+fn resolve_search_encoding(encoding_name: Option<&str>) -> Result<Option<grep::searcher::Encoding>, String> {
+    match encoding_name {
+        None => Ok(None),
+        Some(name) => {
+            let encoding = grep::searcher::Encoding::new(name)
+                .map_err(|e| format!("Unknown encoding '{}': {}", name, e))?;
+            Ok(Some(encoding))
+        }
+    }
+}
+
+// Run a single file through the matcher/sink pipeline for the requested
+// output mode and fold the result into the shared collector. Shared between
+// the parallel directory walk and the indexed-file search path below.
+fn search_one_file(
+    path: &Path,
+    matcher: &AnySearchMatcher,
+    mode: OutputMode,
+    before_context: usize,
+    after_context: usize,
+    search_encoding: &Option<grep::searcher::Encoding>,
+    collector: &Mutex<SearchCollector>,
+) {
+    let mut searcher_builder = SearcherBuilder::new();
+    searcher_builder
+        .binary_detection(BinaryDetection::quit(b'\x00'))
+        .line_number(true)
+        .before_context(before_context)
+        .after_context(after_context)
+        .encoding(search_encoding.clone());
+    let mut searcher = searcher_builder.build();
+
+    match mode {
+        OutputMode::Matches => {
+            let remaining = MAX_SEARCH_RESULTS.saturating_sub(collector.lock().unwrap().len());
+            if remaining == 0 {
+                return;
+            }
+            let mut sink = MatchSink::new(path.to_path_buf(), remaining);
+            if let Err(e) = searcher.search_path(matcher, path, &mut sink) {
+                tracing::debug!("Could not search file {}: {}", path.display(), e);
+                return;
+            }
+            collector.lock().unwrap().record_hit_lines(sink.hits);
+        }
+        OutputMode::Count => {
+            let mut sink = CountSink::new(usize::MAX);
+            if let Err(e) = searcher.search_path(matcher, path, &mut sink) {
+                tracing::debug!("Could not search file {}: {}", path.display(), e);
+                return;
+            }
+            collector.lock().unwrap().record_file_count(path, sink.count);
+        }
+        OutputMode::Files => {
+            // Short-circuit on the first hit: we only need to know whether
+            // this file matched at all.
+            let mut sink = CountSink::new(1);
+            if let Err(e) = searcher.search_path(matcher, path, &mut sink) {
+                tracing::debug!("Could not search file {}: {}", path.display(), e);
+                return;
+            }
+            collector.lock().unwrap().record_file_count(path, sink.count);
+        }
+    }
+}
+
+// A single indexed file and the mtime it had the last time it was stat'd.
+#[derive(Debug, Clone)]
+struct IndexedFile {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+// The cached crawl of one directory: its files, which file-type filters the
+// crawl was restricted to (empty means every file was considered), and the
+// root directory's own mtime at crawl time, used to cheaply notice files
+// added directly under the root since the last build.
+#[derive(Debug, Default)]
+struct IndexedDirectory {
+    files: Vec<IndexedFile>,
+    extensions: HashSet<String>,
+    root_modified: Option<SystemTime>,
+}
+
+// Caches the result of walking a directory so repeated `search` calls can
+// reuse the file list instead of re-walking the tree each time. Keyed by the
+// root path together with whether `.gitignore` was respected, since that
+// changes which files belong in the crawl.
+#[derive(Debug, Default)]
+struct SearchIndex {
+    directories: Mutex<HashMap<(PathBuf, bool), IndexedDirectory>>,
+}
+
+impl SearchIndex {
+    // Walk `root` with `ignore::WalkBuilder` and (re)store its file list,
+    // tagging the crawl with the extensions it was restricted to.
+    fn build(&self, root: &Path, extensions: &HashSet<String>, respect_gitignore: bool) -> Result<usize, String> {
+        let mut builder = WalkBuilder::new(root);
+        builder
+            .hidden(false)
+            .ignore(respect_gitignore)
+            .git_ignore(respect_gitignore)
+            .git_global(respect_gitignore)
+            .git_exclude(respect_gitignore)
+            .max_depth(Some(10));
+
+        if !extensions.is_empty() {
+            let mut types_builder = TypesBuilder::new();
+            types_builder.add_defaults();
+            for extension in extensions {
+                types_builder.select(extension);
+            }
+            let types = types_builder
+                .build()
+                .map_err(|e| format!("Failed to build file-type filters: {}", e))?;
+            builder.types(types);
+        }
+
+        let mut files = Vec::new();
+        for entry in builder.build() {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    tracing::debug!("Error walking directory while indexing: {}", e);
+                    continue;
+                }
+            };
+            let path = entry.path();
+            if !path.is_file() {
+                continue;
+            }
+            let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+            files.push(IndexedFile {
+                path: path.to_path_buf(),
+                modified,
+            });
+        }
+
+        let root_modified = fs::metadata(root).and_then(|m| m.modified()).ok();
+        let count = files.len();
+        self.directories.lock().unwrap().insert(
+            (root.to_path_buf(), respect_gitignore),
+            IndexedDirectory {
+                files,
+                extensions: extensions.clone(),
+                root_modified,
+            },
+        );
+        Ok(count)
+    }
+
+    // Return the cached file list for `root`, building it on first use. On
+    // a cache hit, each entry is re-stat'd so deleted or modified files are
+    // refreshed without re-walking the whole tree; a request for extensions
+    // the cache doesn't cover, or a root directory whose own mtime has moved
+    // on since the crawl (a cheap signal that an entry was added or removed
+    // directly under it), falls back to a fresh `build`. The returned list is
+    // always narrowed to the requested extensions, since a cache built for a
+    // broader (or unrestricted) type set may cover more than was asked for.
+    fn get_or_build(
+        &self,
+        root: &Path,
+        extensions: &HashSet<String>,
+        respect_gitignore: bool,
+    ) -> Result<Vec<PathBuf>, String> {
+        let key = (root.to_path_buf(), respect_gitignore);
+        let current_root_modified = fs::metadata(root).and_then(|m| m.modified()).ok();
+
+        {
+            let mut directories = self.directories.lock().unwrap();
+            if let Some(indexed) = directories.get_mut(&key) {
+                let covered =
+                    indexed.extensions.is_empty() || (!extensions.is_empty() && extensions.is_subset(&indexed.extensions));
+                let root_unchanged = indexed.root_modified == current_root_modified;
+                if covered && root_unchanged {
+                    indexed.files.retain_mut(|file| {
+                        let modified = fs::metadata(&file.path).and_then(|m| m.modified()).ok();
+                        let still_present = modified.is_some();
+                        file.modified = modified;
+                        still_present
+                    });
+                    let files = indexed.files.iter().map(|f| f.path.clone()).collect();
+                    return Ok(Self::filter_by_extensions(files, extensions));
+                }
+            }
+        }
+
+        self.build(root, extensions, respect_gitignore)?;
+        let directories = self.directories.lock().unwrap();
+        let files = directories[&key].files.iter().map(|f| f.path.clone()).collect();
+        Ok(Self::filter_by_extensions(files, extensions))
+    }
+
+    // Narrow a file list down to the ones matching the requested file-type
+    // filters. No-op when no filters were requested.
+    fn filter_by_extensions(files: Vec<PathBuf>, extensions: &HashSet<String>) -> Vec<PathBuf> {
+        if extensions.is_empty() {
+            return files;
+        }
+        let mut types_builder = TypesBuilder::new();
+        types_builder.add_defaults();
+        for extension in extensions {
+            types_builder.select(extension);
+        }
+        let types = match types_builder.build() {
+            Ok(types) => types,
+            Err(_) => return files,
+        };
+        files
+            .into_iter()
+            .filter(|path| types.matched(path, false).is_whitelist())
+            .collect()
+    }
+}
+
 // Search parameters: directory path and search keyword
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
 pub struct SearchParams {
@@ -15,6 +647,32 @@ pub struct SearchParams {
     pub directory: String,
     #[schemars(description = "Keyword to search for")]
     pub keyword: String,
+    #[schemars(description = "Number of worker threads to use for the directory walk (defaults to the number of CPUs)")]
+    pub threads: Option<usize>,
+    #[serde(default)]
+    #[schemars(description = "Glob patterns to restrict the search to; prefix a pattern with '!' to exclude matching paths")]
+    pub globs: Vec<String>,
+    #[serde(default)]
+    #[schemars(description = "Named file-type filters to restrict the search to, e.g. \"rust\", \"py\", \"md\"")]
+    pub types: Vec<String>,
+    #[serde(default)]
+    #[schemars(description = "Respect .gitignore/.ignore files instead of searching everything (default: false)")]
+    pub respect_gitignore: bool,
+    #[serde(default)]
+    #[schemars(description = "Number of lines of context to show before each match")]
+    pub before_context: usize,
+    #[serde(default)]
+    #[schemars(description = "Number of lines of context to show after each match")]
+    pub after_context: usize,
+    #[serde(default)]
+    #[schemars(description = "Regex engine to use: \"default\" (Rust's regex crate) or \"pcre2\" (supports lookaround and backreferences)")]
+    pub engine: Option<String>,
+    #[serde(default)]
+    #[schemars(description = "Encoding to read files as (e.g. \"utf-8\", \"utf-16le\", \"shift_jis\"); auto-detected from a BOM or assumed UTF-8 if omitted")]
+    pub encoding: Option<String>,
+    #[serde(default)]
+    #[schemars(description = "What to return: \"matches\" (default, matched lines), \"count\" (matches per file), or \"files\" (just the files that matched)")]
+    pub output_mode: Option<String>,
 }
 
 // File content parameters: file path
@@ -22,16 +680,52 @@ pub struct SearchParams {
 pub struct FileContentParams {
     #[schemars(description = "Path to the file to read")]
     pub file_path: String,
+    #[serde(default)]
+    #[schemars(description = "Encoding to read the file as (e.g. \"utf-8\", \"utf-16le\", \"shift_jis\"); auto-detected from a BOM or assumed UTF-8 if omitted")]
+    pub encoding: Option<String>,
+    #[serde(default)]
+    #[schemars(description = "First line to return, 1-based and inclusive (defaults to the start of the file)")]
+    pub start_line: Option<usize>,
+    #[serde(default)]
+    #[schemars(description = "Last line to return, 1-based and inclusive (defaults to the end of the file)")]
+    pub end_line: Option<usize>,
+    #[serde(default)]
+    #[schemars(description = "Stop and return a truncation marker once this many bytes of content have been read")]
+    pub max_bytes: Option<usize>,
 }
 
-// Main tool struct
+// Index parameters: the directory to warm or refresh in the search index
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct IndexParams {
+    #[schemars(description = "Path to the directory to index")]
+    pub directory: String,
+    #[serde(default)]
+    #[schemars(description = "Limit indexing to these file-type filters, e.g. \"rust\", \"py\" (defaults to every file)")]
+    pub types: Vec<String>,
+    #[serde(default)]
+    #[schemars(description = "Respect .gitignore/.ignore files while indexing (default: false)")]
+    pub respect_gitignore: bool,
+}
+
+// Main tool struct. `index` is shared across clones so the directory index
+// warmed by one call is visible to every later `search`.
 #[derive(Debug, Clone)]
-pub struct SearchTool;
+pub struct SearchTool {
+    index: Arc<SearchIndex>,
+}
+
+impl Default for SearchTool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 #[tool(tool_box)]
 impl SearchTool {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            index: Arc::new(SearchIndex::default()),
+        }
     }
 
     /// Read and return the content of a specified file
@@ -59,52 +753,76 @@ impl SearchTool {
             ));
         }
 
-        // Try to read the file content
-        match fs::read_to_string(file_path) {
-            Ok(content) => {
-                if content.is_empty() {
-                    Ok("File is empty.".to_string())
-                } else {
-                    Ok(content)
-                }
-            }
-            Err(e) => {
-                // Handle binary files or read errors
-                tracing::error!("Error reading file '{}': {}", file_path.display(), e);
-
-                // Try to read as binary and check if it's a binary file
-                match fs::read(file_path) {
-                    Ok(bytes) => {
-                        // Check if it seems to be a binary file
-                        if bytes.iter().any(|&b| b == 0)
-                            || bytes
-                                .iter()
-                                .filter(|&&b| b < 32 && b != 9 && b != 10 && b != 13)
-                                .count()
-                                > bytes.len() / 10
-                        {
-                            Err(format!(
-                                "The file '{}' appears to be a binary file and cannot be displayed as text",
-                                params.file_path
-                            ))
-                        } else {
-                            Err(format!(
-                                "The file '{}' could not be read as text: {}",
-                                params.file_path, e
-                            ))
-                        }
-                    }
-                    Err(read_err) => Err(format!(
-                        "Error reading file '{}': {}",
-                        params.file_path, read_err
-                    )),
-                }
-            }
+        let content = read_file_range(
+            file_path,
+            params.encoding.as_deref(),
+            params.start_line,
+            params.end_line,
+            params.max_bytes,
+            &params.file_path,
+        )?;
+
+        if content.is_empty() {
+            Ok("File is empty.".to_string())
+        } else {
+            Ok(content)
+        }
+    }
+
+    /// Build (or warm) the search index for a directory ahead of time
+    #[tool(description = "Walk a directory and cache its file list so later `search` calls skip re-walking it")]
+    async fn index_root(&self, #[tool(aggr)] params: IndexParams) -> Result<String, String> {
+        let dir_path = Path::new(&params.directory);
+        if !dir_path.exists() {
+            return Err(format!(
+                "The specified path '{}' does not exist",
+                params.directory
+            ));
+        }
+        if !dir_path.is_dir() {
+            return Err(format!(
+                "The specified path '{}' is not a directory",
+                params.directory
+            ));
         }
+
+        let extensions: HashSet<String> = params.types.iter().cloned().collect();
+        let count = self.index.build(dir_path, &extensions, params.respect_gitignore)?;
+        Ok(format!(
+            "Indexed {} file(s) under '{}'.",
+            count, params.directory
+        ))
     }
 
-    /// Perform fast search for keywords in files using ripgrep
-    #[tool(description = "Search for keywords in text files within the specified directory")]
+    /// Force a refresh of an already-indexed directory's file list
+    #[tool(description = "Rebuild the cached file list for a directory, picking up added or removed files")]
+    async fn reindex(&self, #[tool(aggr)] params: IndexParams) -> Result<String, String> {
+        let dir_path = Path::new(&params.directory);
+        if !dir_path.exists() {
+            return Err(format!(
+                "The specified path '{}' does not exist",
+                params.directory
+            ));
+        }
+        if !dir_path.is_dir() {
+            return Err(format!(
+                "The specified path '{}' is not a directory",
+                params.directory
+            ));
+        }
+
+        let extensions: HashSet<String> = params.types.iter().cloned().collect();
+        let count = self.index.build(dir_path, &extensions, params.respect_gitignore)?;
+        Ok(format!(
+            "Reindexed {} file(s) under '{}'.",
+            count, params.directory
+        ))
+    }
+
+    /// Perform fast search for keywords in files using an in-process grep pipeline
+    #[tool(
+        description = "Search for keywords in text files within the specified directory. Reuses the cached index built by index_root/a prior search when available; files added in a subdirectory after the directory was indexed may not appear until `reindex` is called"
+    )]
     async fn search(&self, #[tool(aggr)] params: SearchParams) -> Result<String, String> {
         // Validate directory path
         let dir_path = Path::new(&params.directory);
@@ -127,144 +845,220 @@ impl SearchTool {
             return Err("Search keyword is empty. Please enter a valid keyword.".into());
         }
 
+        let mode = OutputMode::parse(params.output_mode.as_deref())?;
+
         tracing::info!("Starting search for '{}' in {}", params.keyword, params.directory);
 
-        // Method 1: Use ripgrep directly through process
-        let results = self.search_with_ripgrep(&params.directory, &params.keyword)?;
-        
+        let results = self.search_in_process(&params, mode)?;
+
         if results.is_empty() {
             Ok(format!(
                 "No search results for keyword '{}'.",
                 params.keyword
             ))
         } else {
-            Ok(format!(
-                "Search results:\n{}",
-                results
-            ))
+            let header = match mode {
+                OutputMode::Matches => "Search results:",
+                OutputMode::Count => "Match counts per file:",
+                OutputMode::Files => "Files containing a match:",
+            };
+            Ok(format!("{}\n{}", header, results.join("\n")))
         }
     }
 
-    // Helper method to search using ripgrep process
-    fn search_with_ripgrep(&self, directory: &str, keyword: &str) -> Result<String, String> {
-        // Run ripgrep command
-        let output = Command::new("rg")
-            .arg("--json")           // Output in JSON format
-            .arg("--max-count=10")   // Limit to 10 matches per file
-            .arg("--max-depth=10")   // Limit directory depth
-            .arg("--ignore-case")    // Case insensitive search
-            .arg("--no-ignore")      // Don't respect .gitignore
-            .arg("--hidden")         // Include hidden files
-            .arg(keyword)            // Search pattern
-            .arg(directory)          // Directory to search
-            .output()
-            .map_err(|e| format!("Failed to execute ripgrep: {}", e))?;
+    // Run the search entirely in-process using the `grep` crate. Ad-hoc glob
+    // overrides always re-walk the tree (they're too dynamic to cache), but
+    // the common case consults `self.index` first so repeated searches over
+    // the same directory skip re-walking it. Hits are funnelled into a
+    // shared, mutex-guarded collector and the search stops early once
+    // `MAX_SEARCH_RESULTS` is reached.
+    fn search_in_process(&self, params: &SearchParams, mode: OutputMode) -> Result<Vec<String>, String> {
+        let matcher = build_matcher(&params.keyword, params.engine.as_deref().unwrap_or("default"))?;
+        let search_encoding = resolve_search_encoding(params.encoding.as_deref())?;
+        let threads = params.threads.unwrap_or_else(num_cpus::get).max(1);
 
-        if !output.status.success() && !output.stdout.is_empty() {
-            // If ripgrep fails but has output, we still try to parse it
-            tracing::warn!("ripgrep exited with non-zero status: {}", output.status);
-        }
+        let collector: Arc<Mutex<SearchCollector>> = Arc::new(Mutex::new(SearchCollector::new(mode)));
 
-        // Parse the JSON output
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let mut results = String::new();
-        let mut count = 0;
+        if params.globs.is_empty() {
+            let extensions: HashSet<String> = params.types.iter().cloned().collect();
+            let directory = Path::new(&params.directory);
+            let files = self
+                .index
+                .get_or_build(directory, &extensions, params.respect_gitignore)?;
 
-        for line in stdout.lines() {
-            if line.trim().is_empty() {
-                continue;
-            }
-
-            // Parse the JSON line
-            match serde_json::from_str::<serde_json::Value>(line) {
-                Ok(json) => {
-                    if let Some(match_type) = json.get("type").and_then(|t| t.as_str()) {
-                        if match_type == "match" {
-                            if let (Some(path), Some(lines)) = (
-                                json.get("data").and_then(|d| d.get("path")).and_then(|p| p.get("text")).and_then(|t| t.as_str()),
-                                json.get("data").and_then(|d| d.get("lines")).and_then(|l| l.get("text")).and_then(|t| t.as_str()),
-                            ) {
-                                count += 1;
-                                results.push_str(&format!("Hit: {} - {}\n", path, lines.trim()));
+            std::thread::scope(|scope| {
+                for chunk in files.chunks(files.len().div_ceil(threads).max(1)) {
+                    let matcher = &matcher;
+                    let search_encoding = &search_encoding;
+                    let collector = &collector;
+                    scope.spawn(move || {
+                        for path in chunk {
+                            if collector.lock().unwrap().len() >= MAX_SEARCH_RESULTS {
+                                break;
                             }
+                            search_one_file(
+                                path,
+                                matcher,
+                                mode,
+                                params.before_context,
+                                params.after_context,
+                                search_encoding,
+                                collector,
+                            );
                         }
-                    }
+                    });
                 }
-                Err(e) => {
-                    tracing::error!("Failed to parse ripgrep JSON output: {}", e);
-                    tracing::debug!("Problematic line: {}", line);
+            });
+        } else {
+            let directory = params.directory.as_str();
+            let mut builder = WalkBuilder::new(directory);
+            builder
+                .hidden(false) // Include hidden files
+                .ignore(params.respect_gitignore)
+                .git_ignore(params.respect_gitignore)
+                .git_global(params.respect_gitignore)
+                .git_exclude(params.respect_gitignore)
+                .max_depth(Some(10)) // Limit directory depth
+                .threads(threads);
+
+            let mut override_builder = OverrideBuilder::new(directory);
+            for glob in &params.globs {
+                override_builder
+                    .add(glob)
+                    .map_err(|e| format!("Invalid glob pattern '{}': {}", glob, e))?;
+            }
+            let overrides = override_builder
+                .build()
+                .map_err(|e| format!("Failed to build glob overrides: {}", e))?;
+            builder.overrides(overrides);
+
+            if !params.types.is_empty() {
+                let mut types_builder = TypesBuilder::new();
+                types_builder.add_defaults();
+                for type_name in &params.types {
+                    types_builder.select(type_name);
                 }
+                let types = types_builder
+                    .build()
+                    .map_err(|e| format!("Failed to build file-type filters: {}", e))?;
+                builder.types(types);
             }
-        }
 
-        if count == 0 {
-            // Fallback to grep-rs library if ripgrep command fails or returns no results
-            return self.search_with_grep_rs(directory, keyword);
+            let walker = builder.build_parallel();
+
+            walker.run(|| {
+                let matcher = matcher.clone();
+                let search_encoding = search_encoding.clone();
+                let collector = Arc::clone(&collector);
+
+                Box::new(move |result| {
+                    if collector.lock().unwrap().len() >= MAX_SEARCH_RESULTS {
+                        return WalkState::Quit;
+                    }
+
+                    let entry = match result {
+                        Ok(entry) => entry,
+                        Err(e) => {
+                            tracing::debug!("Error walking directory: {}", e);
+                            return WalkState::Continue;
+                        }
+                    };
+
+                    let path = entry.path();
+                    if !path.is_file() {
+                        return WalkState::Continue;
+                    }
+
+                    search_one_file(
+                        path,
+                        &matcher,
+                        mode,
+                        params.before_context,
+                        params.after_context,
+                        &search_encoding,
+                        &collector,
+                    );
+
+                    if collector.lock().unwrap().len() >= MAX_SEARCH_RESULTS {
+                        WalkState::Quit
+                    } else {
+                        WalkState::Continue
+                    }
+                })
+            });
         }
 
-        Ok(results)
+        let collector = Arc::try_unwrap(collector)
+            .map_err(|_| "Failed to collect search results".to_string())?
+            .into_inner()
+            .map_err(|e| format!("Search result lock was poisoned: {}", e))?;
+
+        Ok(collector.into_lines())
+    }
+}
+
+// A `Sink` that records each match (and any requested context lines around
+// it) as formatted lines, up to `limit` lines total for the file it was
+// built for. Non-adjacent match groups are separated with a `--` line, the
+// same way ripgrep separates context blocks.
+struct MatchSink {
+    path: PathBuf,
+    limit: usize,
+    hits: Vec<String>,
+}
+
+impl MatchSink {
+    fn new(path: PathBuf, limit: usize) -> Self {
+        Self {
+            path,
+            limit,
+            hits: Vec::new(),
+        }
     }
+}
 
-    // Fallback method using the grep-rs library
-    fn search_with_grep_rs(&self, directory: &str, pattern: &str) -> Result<String, String> {
-        // Create a matcher for the search pattern
-        let _matcher = RegexMatcher::new(pattern)
-            .map_err(|e| format!("Invalid search pattern: {}", e))?;
+impl Sink for MatchSink {
+    type Error = std::io::Error;
 
-        let mut results = String::new();
-        let mut count = 0;
+    fn matched(&mut self, _searcher: &Searcher, mat: &SinkMatch<'_>) -> Result<bool, Self::Error> {
+        let line_number = mat.line_number().unwrap_or(0);
+        let absolute_offset = mat.absolute_byte_offset();
+        let text = String::from_utf8_lossy(mat.bytes()).trim_end().to_string();
 
-        // Configure the searcher for potential future use
-        let _searcher = SearcherBuilder::new()
-            .binary_detection(BinaryDetection::quit(b'\x00'))
-            .line_number(true)
-            .build();
+        self.hits.push(format!(
+            "Hit: {} - Line {} (offset {}): {}",
+            self.path.display(),
+            line_number,
+            absolute_offset,
+            text
+        ));
 
-        // Walk through files in the directory
-        let walker = WalkBuilder::new(directory)
-            .hidden(false)        // Include hidden files
-            .ignore(false)        // Don't respect .gitignore
-            .max_depth(Some(10))  // Limit directory depth
-            .build();
+        Ok(self.hits.len() < self.limit)
+    }
 
-        for result in walker {
-            if count >= 10 {
-                // Limit to 10 results for performance
-                break;
-            }
+    fn context(&mut self, _searcher: &Searcher, ctx: &SinkContext<'_>) -> Result<bool, Self::Error> {
+        let line_number = ctx.line_number().unwrap_or(0);
+        let text = String::from_utf8_lossy(ctx.bytes()).trim_end().to_string();
 
-            match result {
-                Ok(entry) => {
-                    let path = entry.path();
-                    if path.is_file() {
-                        // Try to search the file
-                        match fs::read_to_string(path) {
-                            Ok(content) => {
-                                if content.contains(pattern) {
-                                    count += 1;
-                                    // Find the matching line
-                                    for (i, line) in content.lines().enumerate() {
-                                        if line.contains(pattern) {
-                                            results.push_str(&format!("Hit: {} - Line {}: {}\n", 
-                                                path.display(), i + 1, line.trim()));
-                                            break;
-                                        }
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                tracing::debug!("Could not read file {}: {}", path.display(), e);
-                            }
-                        }
-                    }
-                }
-                Err(e) => {
-                    tracing::debug!("Error walking directory: {}", e);
-                }
+        self.hits.push(format!(
+            "Context: {} - Line {}: {}",
+            self.path.display(),
+            line_number,
+            text
+        ));
+
+        Ok(self.hits.len() < self.limit)
+    }
+
+    fn context_break(&mut self, _searcher: &Searcher) -> Result<bool, Self::Error> {
+        if !self.hits.is_empty() {
+            // Leave room for at least one more line after the separator.
+            if self.hits.len() + 1 >= self.limit {
+                return Ok(false);
             }
+            self.hits.push("--".to_string());
         }
-
-        Ok(results)
+        Ok(true)
     }
 }
 
@@ -286,3 +1080,50 @@ impl ServerHandler for SearchTool {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Regression test for the per-file budget overrun where each worker
+    // handed every file its own full `MAX_SEARCH_RESULTS` budget instead of
+    // the remaining share of it, letting a run across several matching
+    // files collect far more than `MAX_SEARCH_RESULTS` lines in total.
+    #[test]
+    fn search_one_file_respects_shared_match_budget() {
+        let dir = tempfile::tempdir().unwrap();
+        let matcher = build_matcher("needle", "default").unwrap();
+        let collector = Mutex::new(SearchCollector::new(OutputMode::Matches));
+
+        for i in 0..3 {
+            let path = dir.path().join(format!("file{}.txt", i));
+            fs::write(&path, "needle line\n".repeat(60)).unwrap();
+        }
+
+        for entry in fs::read_dir(dir.path()).unwrap() {
+            let path = entry.unwrap().path();
+            search_one_file(&path, &matcher, OutputMode::Matches, 0, 0, &None, &collector);
+        }
+
+        assert!(collector.lock().unwrap().len() <= MAX_SEARCH_RESULTS);
+    }
+
+    // Regression test for the cache-hit path silently ignoring the
+    // requested `types` filter whenever the cached crawl was unrestricted
+    // (or otherwise a superset of what was asked for).
+    #[test]
+    fn get_or_build_narrows_cached_results_to_requested_extensions() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("a.rs"), "fn main() {}").unwrap();
+        fs::write(dir.path().join("b.txt"), "hello").unwrap();
+
+        let index = SearchIndex::default();
+        index.build(dir.path(), &HashSet::new(), false).unwrap();
+
+        let rust_only: HashSet<String> = ["rust".to_string()].into_iter().collect();
+        let files = index.get_or_build(dir.path(), &rust_only, false).unwrap();
+
+        assert!(!files.is_empty());
+        assert!(files.iter().all(|p| p.extension().is_some_and(|e| e == "rs")));
+    }
+}